@@ -9,22 +9,40 @@ impl Default for ElementId {
     }
 }
 
+/// Identifies a mounted component instance across renders, independent of whatever element(s)
+/// its subtree currently owns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScopeId(pub usize);
+
 pub struct Arena {
     counter: NonZeroUsize,
+    free: Vec<ElementId>,
 }
 
 impl Default for Arena {
     fn default() -> Self {
         Self {
             counter: NonZeroUsize::new(1).unwrap(),
+            free: Vec::new(),
         }
     }
 }
 
 impl Arena {
+    /// Hand out an id, reusing one from a removed element if one is available so the numeric
+    /// range stays compact for renderers that index into a dense `Vec` by `ElementId`.
     pub fn next(&mut self) -> ElementId {
+        if let Some(id) = self.free.pop() {
+            return id;
+        }
+
         let id = self.counter;
         self.counter = NonZeroUsize::new(self.counter.get() + 1).unwrap();
         ElementId(id)
     }
+
+    /// Return an id to the free list so a future `next()` call can reuse it.
+    pub fn reclaim(&mut self, id: ElementId) {
+        self.free.push(id);
+    }
 }
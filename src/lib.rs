@@ -28,55 +28,243 @@ replace_element(instance: 0, el: "aaabcdf");         |
 
 */
 
-use arena::Arena;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use arena::{Arena, ElementId, ScopeId};
 use mutation::Mutation;
-use node::{DynamicNode, VTemplate};
+use node::{DynamicNode, RenderReturn, VTemplate};
 
-use crate::node::{Attribute, AttributeLocation, Template, TemplateAttribute, TemplateNode};
+use crate::node::Template;
 
 mod arena;
-mod diff;
 mod mutation;
 mod node;
 
 #[derive(Default)]
 pub struct VirtualDom {
     arena: Arena,
+
+    /// Root element mounted by each live component, keyed by its stable scope id.
+    scopes: HashMap<ScopeId, ElementId>,
+    scope_counter: usize,
+
+    /// Per-template dynamic-slot traversal order, keyed by `Template::id`.
+    path_cache: HashMap<&'static str, Rc<[PathEntry]>>,
+}
+
+/// A dynamic attribute or dynamic node slot, identified by its index into
+/// `Template::attr_pathways`/`Template::node_pathways` respectively.
+#[derive(Clone, Copy)]
+enum PathEntry {
+    Attr(usize),
+    Node(usize),
+}
+
+/// Order `template`'s dynamic slots by the path each one targets, so visiting them in this
+/// order walks the template depth-first without backtracking.
+fn compute_path_order(template: &Template) -> Vec<PathEntry> {
+    let mut order: Vec<PathEntry> = (0..template.attr_pathways.len())
+        .map(PathEntry::Attr)
+        .chain((0..template.node_pathways.len()).map(PathEntry::Node))
+        .collect();
+
+    order.sort_by_key(|entry| match *entry {
+        PathEntry::Attr(idx) => template.attr_pathways[idx],
+        PathEntry::Node(idx) => template.node_pathways[idx],
+    });
+
+    order
 }
 
 impl VirtualDom {
+    fn next_scope(&mut self) -> ScopeId {
+        let id = ScopeId(self.scope_counter);
+        self.scope_counter += 1;
+        id
+    }
+
     pub fn create<'a>(&mut self, mutations: &mut Vec<Mutation<'a>>, template: &'a VTemplate<'a>) {
         let id = self.arena.next();
+        template.node_id.set(id);
 
         mutations.push(Mutation::LoadTemplate {
             name: template.template.id,
             id,
         });
 
-        for (idx, dyn_node) in template.dynamic_attrs.iter().enumerate() {
-            let id = self.arena.next();
+        // Assign every dynamic slot's id up front so they can be emitted in traversal order
+        // below, independent of their position in `dynamic_attrs`/`dynamic_nodes`.
+        for dyn_node in template.dynamic_attrs.iter() {
+            dyn_node.mounted_element.set(self.arena.next());
+        }
+        for dyn_node in template.dynamic_nodes.iter() {
+            if let DynamicNode::Text { id, .. } = dyn_node {
+                id.set(self.arena.next());
+            }
+        }
 
-            let path = template.template.attr_pathways[idx];
-            mutations.push(Mutation::AssignId { path, id });
+        // Emit AssignId/HydrateText sorted by the path they target rather than array order, so
+        // a stack-machine renderer can walk the mutation stream as pure forward cursor motion
+        // instead of backtracking.
+        for entry in self.path_order(&template.template).iter() {
+            match *entry {
+                PathEntry::Attr(idx) => {
+                    let dyn_node = &template.dynamic_attrs[idx];
+                    let id = dyn_node.mounted_element.get();
+                    let path = template.template.attr_pathways[idx];
+                    mutations.push(Mutation::AssignId { path, id });
 
-            for attr in dyn_node.attrs {
-                mutations.push(Mutation::SetAttribute {
-                    name: attr.name,
-                    value: attr.value,
-                    id,
-                });
+                    for attr in dyn_node.attrs {
+                        mutations.push(Mutation::SetAttribute {
+                            name: attr.name,
+                            value: attr.value,
+                            id,
+                        });
+                    }
+                }
+                PathEntry::Node(idx) => {
+                    if let DynamicNode::Text { value, id } = &template.dynamic_nodes[idx] {
+                        let path = template.template.node_pathways[idx];
+                        mutations.push(Mutation::HydrateText {
+                            path,
+                            value,
+                            id: id.get(),
+                        });
+                    }
+                }
             }
         }
 
-        for (idx, dyn_node) in template.dynamic_nodes.iter().enumerate() {
+        for dyn_node in template.dynamic_nodes.iter() {
             match dyn_node {
-                DynamicNode::Component { name } => todo!("not yet"),
-                DynamicNode::Text { value, id } => {
-                    let id = self.arena.next();
-                    let path = template.template.node_pathways[idx];
-                    mutations.push(Mutation::HydrateText { path, value, id });
+                DynamicNode::Component {
+                    scope,
+                    props,
+                    render,
+                    mounted,
+                    ..
+                } => {
+                    let scope_id = self.next_scope();
+                    scope.set(scope_id);
+                    let render_return = render(*props);
+                    mounted.set(Some(render_return));
+                    self.mount_component(mutations, scope_id, render_return);
+                }
+                DynamicNode::Text { .. } => {} // handled above, in path order
+                DynamicNode::Fragment {
+                    children,
+                    placeholder,
+                } => {
+                    if children.is_empty() {
+                        let id = self.arena.next();
+                        placeholder.set(id);
+                        mutations.push(Mutation::CreatePlaceholder { id });
+                    } else {
+                        for child in children.iter() {
+                            self.create(mutations, child);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mounts whatever a component rendered — a real subtree, or a placeholder standing in for
+    /// an error/pending state — and records its root id under `scope`.
+    fn mount_component<'a>(
+        &mut self,
+        mutations: &mut Vec<Mutation<'a>>,
+        scope: ScopeId,
+        render_return: &'a RenderReturn<'a>,
+    ) -> ElementId {
+        let id = match render_return {
+            RenderReturn::Ready(subtree) => {
+                self.create(mutations, subtree);
+                subtree.node_id.get()
+            }
+            RenderReturn::Err(_) | RenderReturn::Pending => {
+                let id = self.arena.next();
+                mutations.push(Mutation::CreatePlaceholder { id });
+                id
+            }
+        };
+
+        self.scopes.insert(scope, id);
+        id
+    }
+
+    /// Tears down whatever a component mounted under `scope` — a full subtree or just a
+    /// placeholder — handing every id it owned back to the arena. Returns the root id that was
+    /// freed, if any, so the caller can target it with a `Replace` mutation.
+    ///
+    /// Only safe to call once any mutation referencing the freed id(s) has already been pushed:
+    /// freeing hands them to the arena's free list, so a `create` call made afterwards can (and
+    /// typically will) immediately reuse them.
+    fn reclaim_component(&mut self, scope: ScopeId, render_return: &RenderReturn) -> Option<ElementId> {
+        let mounted_id = self.scopes.remove(&scope);
+        self.free_component(mounted_id, render_return);
+        mounted_id
+    }
+
+    /// Hands a component's owned id(s) back to the arena, given the root id it was mounted
+    /// under. Unlike `reclaim_component`, this doesn't touch `self.scopes`, so it's safe to call
+    /// after a replacement component has already claimed the same scope key.
+    fn free_component(&mut self, mounted_id: Option<ElementId>, render_return: &RenderReturn) {
+        match render_return {
+            RenderReturn::Ready(subtree) => self.reclaim(subtree),
+            RenderReturn::Err(_) | RenderReturn::Pending => {
+                if let Some(id) = mounted_id {
+                    self.arena.reclaim(id);
+                }
+            }
+        }
+    }
+
+    /// Returns the traversal order in which `template`'s dynamic attr/node slots should be
+    /// visited, sorted by the path each one targets. Cached per template id, since every
+    /// instance of the same template shares the same order.
+    fn path_order(&mut self, template: &Template) -> Rc<[PathEntry]> {
+        if let Some(cached) = self.path_cache.get(template.id) {
+            return cached.clone();
+        }
+
+        let order: Rc<[PathEntry]> = compute_path_order(template).into();
+        self.path_cache.insert(template.id, order.clone());
+        order
+    }
+
+    /// Hand the ids of `template` and everything it owns back to the arena: its own mounted id,
+    /// its dynamic attributes' target ids, and (recursively) any fragment children's ids.
+    fn reclaim(&mut self, template: &VTemplate) {
+        self.arena.reclaim(template.node_id.get());
+
+        for attr in template.dynamic_attrs {
+            self.arena.reclaim(attr.mounted_element.get());
+        }
+
+        for node in template.dynamic_nodes {
+            match node {
+                DynamicNode::Text { id, .. } => self.arena.reclaim(id.get()),
+                DynamicNode::Fragment {
+                    children,
+                    placeholder,
+                } => {
+                    if children.is_empty() {
+                        self.arena.reclaim(placeholder.get());
+                    } else {
+                        for child in children.iter() {
+                            self.reclaim(child);
+                        }
+                    }
+                }
+                DynamicNode::Component { scope, mounted, .. } => {
+                    let render_return = mounted
+                        .get()
+                        .expect("component is reclaimed only after create/diff mounted it");
+                    self.reclaim_component(scope.get(), render_return);
                 }
-                DynamicNode::Fragment { children } => todo!(),
             }
         }
     }
@@ -91,11 +279,16 @@ impl VirtualDom {
             self.create(mutations, right);
             let id = left.node_id.get();
             mutations.push(Mutation::Replace { id });
+            self.reclaim(left);
             return;
         }
 
+        right.node_id.set(left.node_id.get());
+
         // Set the attributes
         for (left_node, right_node) in left.dynamic_attrs.iter().zip(right.dynamic_attrs.iter()) {
+            right_node.mounted_element.set(left_node.mounted_element.get());
+
             for (left, right) in left_node.attrs.iter().zip(right_node.attrs.iter()) {
                 // use ptr shortcircuting before the memcmp
                 if !std::ptr::eq(left.value, right.value) && left.value != right.value {
@@ -110,7 +303,99 @@ impl VirtualDom {
 
         for (left, right) in left.dynamic_nodes.iter().zip(right.dynamic_nodes.iter()) {
             match (left, right) {
-                (DynamicNode::Component { .. }, DynamicNode::Component { .. }) => todo!(),
+                (
+                    DynamicNode::Component {
+                        name: n1,
+                        scope: s1,
+                        props: p1,
+                        mounted: old_mounted,
+                        ..
+                    },
+                    DynamicNode::Component {
+                        name: n2,
+                        scope: s2,
+                        props: p2,
+                        render: r2,
+                        memo: m2,
+                        mounted: new_mounted,
+                    },
+                ) => {
+                    if n1 != n2 {
+                        // Different component types entirely: there's no subtree to reconcile
+                        // against, so mount the new one fresh, replace the old one, and only
+                        // then free the old one's ids — freeing them first would let the new
+                        // `create` call immediately reuse them, so the `Replace` below would
+                        // target an id the new content already claimed.
+                        let old_return = old_mounted
+                            .get()
+                            .expect("component is diffed only after create mounted it");
+                        let old_id = self.scopes.remove(&s1.get());
+
+                        let scope_id = self.next_scope();
+                        s2.set(scope_id);
+                        let new_return = r2(*p2);
+                        new_mounted.set(Some(new_return));
+                        self.mount_component(mutations, scope_id, new_return);
+
+                        if let Some(old_id) = old_id {
+                            mutations.push(Mutation::Replace { id: old_id });
+                        }
+
+                        self.free_component(old_id, old_return);
+                    } else {
+                        // The scope identity survives a props update on the same component type.
+                        s2.set(s1.get());
+
+                        let old_return = old_mounted
+                            .get()
+                            .expect("component is diffed only after create mounted it");
+
+                        if !m2(*p1, *p2) {
+                            let new_return = r2(*p2);
+                            new_mounted.set(Some(new_return));
+
+                            match (old_return, new_return) {
+                                (RenderReturn::Ready(old_subtree), RenderReturn::Ready(new_subtree)) => {
+                                    self.diff(mutations, old_subtree, new_subtree);
+                                    self.scopes.insert(s2.get(), new_subtree.node_id.get());
+                                }
+                                (_, RenderReturn::Ready(_)) => {
+                                    // Err/Pending -> Ok: swap the placeholder for a freshly
+                                    // created subtree. Mount first and free the old placeholder's
+                                    // id only after `Replace` is pushed, so `create` can't reuse
+                                    // it out from under the mutation that's about to reference it.
+                                    let old_id = self.scopes.get(&s2.get()).copied();
+                                    self.mount_component(mutations, s2.get(), new_return);
+                                    if let Some(old_id) = old_id {
+                                        mutations.push(Mutation::Replace { id: old_id });
+                                    }
+                                    self.free_component(old_id, old_return);
+                                }
+                                (RenderReturn::Ready(_), _) => {
+                                    // Ok -> Err/Pending: tear down the subtree and drop in the
+                                    // boundary's placeholder. Same ordering as above: mount, then
+                                    // replace, then free the old subtree's ids.
+                                    let old_id = self.scopes.get(&s2.get()).copied();
+                                    self.mount_component(mutations, s2.get(), new_return);
+                                    if let Some(old_id) = old_id {
+                                        mutations.push(Mutation::Replace { id: old_id });
+                                    }
+                                    self.free_component(old_id, old_return);
+                                }
+                                _ => {
+                                    // Err/Pending -> Err/Pending: the placeholder stays exactly
+                                    // where it is until a future render resolves it.
+                                }
+                            }
+                        } else {
+                            // Props are unchanged, so skip re-rendering and leave the previously
+                            // mounted ids (already tracked in `self.scopes`) in place — but still
+                            // carry the cached mounted render forward so a later diff against
+                            // `right` has it to compare against.
+                            new_mounted.set(Some(old_return));
+                        }
+                    }
+                }
                 (
                     DynamicNode::Text {
                         value: v1, id: id1, ..
@@ -129,20 +414,213 @@ impl VirtualDom {
                     }
                 }
                 (
-                    DynamicNode::Fragment { children: c1 },
-                    DynamicNode::Fragment { children: c2 },
+                    DynamicNode::Fragment {
+                        children: c1,
+                        placeholder: ph1,
+                    },
+                    DynamicNode::Fragment {
+                        children: c2,
+                        placeholder: ph2,
+                    },
                 ) => {
-                    // todo: keyed diffing
-                    for (left, right) in c1.iter().zip(c2.iter()) {
-                        self.diff(mutations, left, right);
-                    }
+                    self.diff_fragment(mutations, c1, c2, ph1, ph2);
                 }
                 _ => todo!(),
             }
         }
     }
+
+    /// Keyed diffing for a fragment's children: common non-keyed runs at the start and end are
+    /// diffed positionally, and the keyed middle is reconciled by matching keys and moving only
+    /// the children that fell out of order (per the longest increasing subsequence of reused
+    /// positions), so reordering a list costs moves instead of a full re-render.
+    ///
+    /// A fragment with no children at all has nothing to anchor future diffs against, so the
+    /// empty <-> populated transitions are handled separately via `placeholder`.
+    fn diff_fragment<'a>(
+        &mut self,
+        mutations: &mut Vec<Mutation<'a>>,
+        c1: &'a [VTemplate<'a>],
+        c2: &'a [VTemplate<'a>],
+        ph1: &Cell<ElementId>,
+        ph2: &Cell<ElementId>,
+    ) {
+        if c1.is_empty() && c2.is_empty() {
+            ph2.set(ph1.get());
+            return;
+        }
+
+        if c1.is_empty() {
+            // Empty -> populated: create the new children and replace the placeholder with the
+            // first one, chaining the rest in front of it. The first child processed (the
+            // fragment's final one) has nothing after it to anchor against, so it's appended
+            // rather than left with no attaching mutation at all.
+            let mut anchor = None;
+            for (idx, child) in c2.iter().enumerate().rev() {
+                self.create(mutations, child);
+                let id = child.node_id.get();
+                if idx == 0 {
+                    mutations.push(Mutation::Replace { id: ph1.get() });
+                } else {
+                    match anchor {
+                        Some(anchor_id) => mutations.push(Mutation::InsertBefore { id, anchor_id }),
+                        None => mutations.push(Mutation::Append { id }),
+                    }
+                }
+                anchor = Some(id);
+            }
+            self.arena.reclaim(ph1.get());
+            return;
+        }
+
+        if c2.is_empty() {
+            // Populated -> empty: tear down every child and mount a placeholder in their place
+            // so the slot stays addressable.
+            for child in c1.iter() {
+                mutations.push(Mutation::Remove {
+                    id: child.node_id.get(),
+                });
+                self.reclaim(child);
+            }
+            let id = self.arena.next();
+            ph2.set(id);
+            mutations.push(Mutation::CreatePlaceholder { id });
+            return;
+        }
+
+        let mut start = 0;
+        while start < c1.len()
+            && start < c2.len()
+            && c1[start].key.is_none()
+            && c2[start].key.is_none()
+        {
+            self.diff(mutations, &c1[start], &c2[start]);
+            start += 1;
+        }
+
+        let mut old_end = c1.len();
+        let mut new_end = c2.len();
+        while old_end > start
+            && new_end > start
+            && c1[old_end - 1].key.is_none()
+            && c2[new_end - 1].key.is_none()
+        {
+            self.diff(mutations, &c1[old_end - 1], &c2[new_end - 1]);
+            old_end -= 1;
+            new_end -= 1;
+        }
+
+        let old_mid = &c1[start..old_end];
+        let new_mid = &c2[start..new_end];
+
+        let mut old_by_key: HashMap<&str, usize> = HashMap::with_capacity(old_mid.len());
+        for (old_idx, old) in old_mid.iter().enumerate() {
+            if let Some(key) = old.key {
+                old_by_key.insert(key, old_idx);
+            }
+        }
+
+        // Diff every matched pair in place, recording (old index, position in the reused
+        // sequence) so we can run the LIS over just the matched children afterwards.
+        let mut matched = vec![false; old_mid.len()];
+        let mut sequence = Vec::new();
+        let mut old_positions: Vec<Option<(usize, usize)>> = Vec::with_capacity(new_mid.len());
+        for new in new_mid {
+            match new.key.and_then(|key| old_by_key.get(key).copied()) {
+                Some(old_idx) => {
+                    self.diff(mutations, &old_mid[old_idx], new);
+                    matched[old_idx] = true;
+                    let seq_idx = sequence.len();
+                    sequence.push(old_idx);
+                    old_positions.push(Some((old_idx, seq_idx)));
+                }
+                None => old_positions.push(None),
+            }
+        }
+
+        // Old children whose key never reappeared are gone for good.
+        for (old_idx, old) in old_mid.iter().enumerate() {
+            if !matched[old_idx] {
+                mutations.push(Mutation::Remove {
+                    id: old.node_id.get(),
+                });
+                self.reclaim(old);
+            }
+        }
+
+        // Children on the longest increasing subsequence are already in the right relative
+        // order; walk the rest back-to-front, creating new children and moving everyone else
+        // in front of the nearest already-placed sibling. The first one processed (the fragment's
+        // final child), when no unchanged trailing run follows it, has no following sibling to
+        // anchor against, so it's appended instead of being left with no attaching mutation.
+        let keep: HashSet<usize> = longest_increasing_subsequence(&sequence)
+            .into_iter()
+            .collect();
+
+        let mut anchor = (new_end < c2.len()).then(|| c2[new_end].node_id.get());
+
+        for (new_idx, entry) in old_positions.iter().enumerate().rev() {
+            let id = match *entry {
+                Some((old_idx, seq_idx)) => {
+                    let id = old_mid[old_idx].node_id.get();
+                    if !keep.contains(&seq_idx) {
+                        match anchor {
+                            Some(anchor_id) => mutations.push(Mutation::InsertBefore { id, anchor_id }),
+                            None => mutations.push(Mutation::Append { id }),
+                        }
+                    }
+                    id
+                }
+                None => {
+                    let new = &new_mid[new_idx];
+                    self.create(mutations, new);
+                    let id = new.node_id.get();
+                    match anchor {
+                        Some(anchor_id) => mutations.push(Mutation::InsertBefore { id, anchor_id }),
+                        None => mutations.push(Mutation::Append { id }),
+                    }
+                    id
+                }
+            };
+            anchor = Some(id);
+        }
+    }
 }
 
+/// The standard O(n log n) patience-sorting longest increasing subsequence: `tails[k]` tracks
+/// the index into `seq` holding the smallest possible tail value for an increasing run of length
+/// `k + 1`, and predecessor links let us walk back from the final tail to the kept indices.
+fn longest_increasing_subsequence(seq: &[usize]) -> Vec<usize> {
+    let mut tails: Vec<usize> = Vec::new();
+    let mut predecessors: Vec<Option<usize>> = vec![None; seq.len()];
+
+    for (i, &value) in seq.iter().enumerate() {
+        let pos = tails.partition_point(|&t| seq[t] < value);
+
+        if pos > 0 {
+            predecessors[i] = Some(tails[pos - 1]);
+        }
+
+        if pos == tails.len() {
+            tails.push(i);
+        } else {
+            tails[pos] = i;
+        }
+    }
+
+    let mut kept = Vec::with_capacity(tails.len());
+    let mut cursor = tails.last().copied();
+    while let Some(i) = cursor {
+        kept.push(i);
+        cursor = predecessors[i];
+    }
+    kept.reverse();
+    kept
+}
+
+#[cfg(test)]
+use crate::node::{Attribute, AttributeLocation, TemplateAttribute, TemplateNode};
+
 #[test]
 fn makes_muts() {
     let mut dom = VirtualDom::default();
@@ -191,6 +669,7 @@ fn makes_muts() {
 
     let template = VTemplate {
         node_id: Default::default(),
+        key: None,
         template: TEMPLATE,
         dynamic_nodes: &[
             DynamicNode::Text {
@@ -224,6 +703,7 @@ fn makes_muts() {
 
     let template_new = VTemplate {
         node_id: Default::default(),
+        key: None,
         template: TEMPLATE,
         dynamic_nodes: &[
             DynamicNode::Text {
@@ -248,8 +728,753 @@ fn makes_muts() {
 
 #[test]
 fn fragments_too() {
-    //
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    static ITEM_TEMPLATE: Template = Template {
+        id: "item",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    let a_nodes = [DynamicNode::Text {
+        value: "a",
+        id: Default::default(),
+    }];
+    let b_nodes = [DynamicNode::Text {
+        value: "b",
+        id: Default::default(),
+    }];
+    let c_nodes = [DynamicNode::Text {
+        value: "c",
+        id: Default::default(),
+    }];
+
+    let old_children = [
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("a"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &a_nodes,
+            dynamic_attrs: &[],
+        },
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("b"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &b_nodes,
+            dynamic_attrs: &[],
+        },
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("c"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &c_nodes,
+            dynamic_attrs: &[],
+        },
+    ];
+
+    for child in &old_children {
+        dom.create(&mut mutations, child);
+    }
+    dbg!(&mut mutations).clear();
+
+    // Reorder: `c` moves to the front, `a` and `b` keep their relative order.
+    let a2_nodes = [DynamicNode::Text {
+        value: "a",
+        id: Default::default(),
+    }];
+    let b2_nodes = [DynamicNode::Text {
+        value: "b",
+        id: Default::default(),
+    }];
+    let c2_nodes = [DynamicNode::Text {
+        value: "c",
+        id: Default::default(),
+    }];
+
+    let new_children = [
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("c"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &c2_nodes,
+            dynamic_attrs: &[],
+        },
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("a"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &a2_nodes,
+            dynamic_attrs: &[],
+        },
+        VTemplate {
+            node_id: Default::default(),
+            key: Some("b"),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: &b2_nodes,
+            dynamic_attrs: &[],
+        },
+    ];
+
+    let ph1 = Cell::new(ElementId::default());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, &old_children, &new_children, &ph1, &ph2);
+
+    // `a` and `b` are already in relative order (the longest increasing subsequence), so only
+    // `c` should need to move.
+    let moves = mutations
+        .iter()
+        .filter(|m| matches!(m, Mutation::InsertBefore { .. }))
+        .count();
+    assert_eq!(moves, 1);
 }
 
 #[test]
 fn two_strs_same_ptr() {}
+
+#[test]
+fn create_emits_in_path_order() {
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    // The dynamic text at array index 0 targets the *later* path, and index 1 targets the
+    // *earlier* one, so array order and traversal order disagree.
+    static TEMPLATE: Template = Template {
+        id: "out-of-order",
+        root: TemplateNode::Element {
+            tag: "div",
+            namespace: None,
+            attrs: &[],
+            children: &[
+                TemplateNode::DynamicText(0),
+                TemplateNode::DynamicText(1),
+            ],
+        },
+        node_pathways: &[&[1], &[0]],
+        attr_pathways: &[],
+    };
+
+    let template = VTemplate {
+        node_id: Default::default(),
+        key: None,
+        template: TEMPLATE,
+        dynamic_nodes: &[
+            DynamicNode::Text {
+                value: "second",
+                id: Default::default(),
+            },
+            DynamicNode::Text {
+                value: "first",
+                id: Default::default(),
+            },
+        ],
+        dynamic_attrs: &[],
+    };
+
+    dom.create(&mut mutations, &template);
+
+    let texts: Vec<&str> = mutations
+        .iter()
+        .filter_map(|m| match m {
+            Mutation::HydrateText { value, .. } => Some(*value),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(texts, vec!["first", "second"]);
+}
+
+#[test]
+fn components_memoize() {
+    use std::any::Any;
+
+    static OUTER_TEMPLATE: Template = Template {
+        id: "outer",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    static COMPONENT_TEMPLATE: Template = Template {
+        id: "component",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn render(props: &dyn Any) -> &'static RenderReturn<'static> {
+        let value = *props.downcast_ref::<i32>().unwrap();
+        let text: &'static str = Box::leak(value.to_string().into_boxed_str());
+        let dynamic_nodes: &'static [DynamicNode<'static>] = Box::leak(Box::new([DynamicNode::Text {
+            value: text,
+            id: Default::default(),
+        }]));
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: COMPONENT_TEMPLATE,
+            dynamic_nodes,
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn memo(a: &dyn Any, b: &dyn Any) -> bool {
+        a.downcast_ref::<i32>() == b.downcast_ref::<i32>()
+    }
+
+    fn wrap(props: &'static i32) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: OUTER_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Component {
+                name: "Counter",
+                scope: Default::default(),
+                props,
+                render,
+                memo,
+                mounted: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    let props_a: &'static i32 = Box::leak(Box::new(1));
+    let props_b: &'static i32 = Box::leak(Box::new(1));
+    let props_c: &'static i32 = Box::leak(Box::new(2));
+
+    let left: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_a)));
+    dom.create(&mut mutations, left);
+    dbg!(&mut mutations).clear();
+
+    // Equal props (by value, not by reference) should skip re-rendering entirely.
+    let right_same: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_b)));
+    dom.diff(&mut mutations, left, right_same);
+    assert!(mutations.is_empty());
+
+    // Different props should re-render and diff the resulting subtrees.
+    let right_diff: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_c)));
+    dom.diff(&mut mutations, right_same, right_diff);
+    assert_eq!(mutations.len(), 1);
+    assert!(matches!(mutations[0], Mutation::SetText { .. }));
+}
+
+#[test]
+fn suspended_components_mount_a_placeholder() {
+    use std::any::Any;
+
+    static OUTER_TEMPLATE: Template = Template {
+        id: "outer-suspense",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    static COMPONENT_TEMPLATE: Template = Template {
+        id: "component-suspense",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn render_pending(_props: &dyn Any) -> &'static RenderReturn<'static> {
+        Box::leak(Box::new(RenderReturn::Pending))
+    }
+
+    fn render_ready(props: &dyn Any) -> &'static RenderReturn<'static> {
+        let value = *props.downcast_ref::<i32>().unwrap();
+        let text: &'static str = Box::leak(value.to_string().into_boxed_str());
+        let dynamic_nodes: &'static [DynamicNode<'static>] = Box::leak(Box::new([DynamicNode::Text {
+            value: text,
+            id: Default::default(),
+        }]));
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: COMPONENT_TEMPLATE,
+            dynamic_nodes,
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn memo(a: &dyn Any, b: &dyn Any) -> bool {
+        a.downcast_ref::<i32>() == b.downcast_ref::<i32>()
+    }
+
+    fn wrap(props: &'static i32, render: fn(&'static dyn Any) -> &'static RenderReturn<'static>) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: OUTER_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Component {
+                name: "Fetcher",
+                scope: Default::default(),
+                props,
+                render,
+                memo,
+                mounted: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    let props_a: &'static i32 = Box::leak(Box::new(1));
+    let props_b: &'static i32 = Box::leak(Box::new(2));
+
+    let pending: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_a, render_pending)));
+    dom.create(&mut mutations, pending);
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::CreatePlaceholder { .. })));
+    mutations.clear();
+
+    // Once the data resolves, the placeholder is replaced with the real subtree.
+    let ready: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_b, render_ready)));
+    dom.diff(&mut mutations, pending, ready);
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::Replace { .. })));
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::LoadTemplate { .. })));
+}
+
+#[test]
+fn fragments_change_length() {
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    static ITEM_TEMPLATE: Template = Template {
+        id: "item-length",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn item(key: &'static str, value: &'static str) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: Some(key),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Text {
+                value,
+                id: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let empty: &'static [VTemplate<'static>] = &[];
+
+    // Staying empty just carries the placeholder's id forward untouched.
+    let ph1 = Cell::new(dom.arena.next());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, empty, empty, &ph1, &ph2);
+    assert!(mutations.is_empty());
+    assert_eq!(ph2.get(), ph1.get());
+
+    // Growing from zero to one child replaces the placeholder with the new content.
+    let id = ph1.get();
+    mutations.clear();
+    let one_item: &'static [VTemplate<'static>] = Box::leak(Box::new([item("a", "a")]));
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, empty, one_item, &ph1, &ph2);
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::Replace { id: replaced } if *replaced == id)));
+
+    // Growing from one child to three appends the extra two.
+    mutations.clear();
+    let three_items: &'static [VTemplate<'static>] =
+        Box::leak(Box::new([item("a", "a"), item("b", "b"), item("c", "c")]));
+    let ph1 = Cell::new(ElementId::default());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, one_item, three_items, &ph1, &ph2);
+    let creates = mutations
+        .iter()
+        .filter(|m| matches!(m, Mutation::LoadTemplate { .. }))
+        .count();
+    assert_eq!(creates, 2);
+
+    // Shrinking back to empty removes the surviving children and leaves a fresh placeholder.
+    mutations.clear();
+    let ph1 = Cell::new(ElementId::default());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, three_items, empty, &ph1, &ph2);
+    let removes = mutations
+        .iter()
+        .filter(|m| matches!(m, Mutation::Remove { .. }))
+        .count();
+    assert_eq!(removes, 3);
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::CreatePlaceholder { .. })));
+}
+
+#[test]
+fn component_rerender_targets_the_actually_mounted_text_id() {
+    use std::any::Any;
+
+    static OUTER_TEMPLATE: Template = Template {
+        id: "rerender-outer",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    static COMPONENT_TEMPLATE: Template = Template {
+        id: "rerender-component",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn render(props: &dyn Any) -> &'static RenderReturn<'static> {
+        let value = *props.downcast_ref::<i32>().unwrap();
+        let text: &'static str = Box::leak(value.to_string().into_boxed_str());
+        let dynamic_nodes: &'static [DynamicNode<'static>] = Box::leak(Box::new([DynamicNode::Text {
+            value: text,
+            id: Default::default(),
+        }]));
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: COMPONENT_TEMPLATE,
+            dynamic_nodes,
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn memo(a: &dyn Any, b: &dyn Any) -> bool {
+        a.downcast_ref::<i32>() == b.downcast_ref::<i32>()
+    }
+
+    fn wrap(props: &'static i32) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: OUTER_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Component {
+                name: "Counter",
+                scope: Default::default(),
+                props,
+                render,
+                memo,
+                mounted: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    let props_a: &'static i32 = Box::leak(Box::new(1));
+    let props_b: &'static i32 = Box::leak(Box::new(2));
+
+    let left: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_a)));
+    dom.create(&mut mutations, left);
+    let mounted_text_id = mutations
+        .iter()
+        .find_map(|m| match m {
+            Mutation::HydrateText { id, .. } => Some(*id),
+            _ => None,
+        })
+        .expect("create mounts the component's text node");
+    mutations.clear();
+
+    let right: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_b)));
+    dom.diff(&mut mutations, left, right);
+
+    // The `SetText` mutation must target the id that was actually assigned on screen, not a
+    // default id from a freshly (and separately) re-rendered subtree.
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::SetText { id, .. } if *id == mounted_text_id)));
+}
+
+#[test]
+fn ok_to_err_reclaims_the_actually_mounted_ids_not_a_fresh_render() {
+    use std::any::Any;
+
+    static OUTER_TEMPLATE: Template = Template {
+        id: "ok-to-err-outer",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    static COMPONENT_TEMPLATE: Template = Template {
+        id: "ok-to-err-component",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn render_ready(props: &dyn Any) -> &'static RenderReturn<'static> {
+        let value = *props.downcast_ref::<i32>().unwrap();
+        let text: &'static str = Box::leak(value.to_string().into_boxed_str());
+        let dynamic_nodes: &'static [DynamicNode<'static>] = Box::leak(Box::new([DynamicNode::Text {
+            value: text,
+            id: Default::default(),
+        }]));
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: COMPONENT_TEMPLATE,
+            dynamic_nodes,
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn render_err(_props: &dyn Any) -> &'static RenderReturn<'static> {
+        Box::leak(Box::new(RenderReturn::Err(anyhow::anyhow!("boom"))))
+    }
+
+    fn memo(a: &dyn Any, b: &dyn Any) -> bool {
+        a.downcast_ref::<i32>() == b.downcast_ref::<i32>()
+    }
+
+    fn wrap(props: &'static i32, render: fn(&'static dyn Any) -> &'static RenderReturn<'static>) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: OUTER_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Component {
+                name: "Fallible",
+                scope: Default::default(),
+                props,
+                render,
+                memo,
+                mounted: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    let props_a: &'static i32 = Box::leak(Box::new(1));
+    let props_b: &'static i32 = Box::leak(Box::new(2));
+
+    let ready: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_a, render_ready)));
+    dom.create(&mut mutations, ready);
+    let mounted_root_id = mutations
+        .iter()
+        .find_map(|m| match m {
+            Mutation::LoadTemplate { name, id } if *name == COMPONENT_TEMPLATE.id => Some(*id),
+            _ => None,
+        })
+        .expect("create mounts the component's root template");
+    mutations.clear();
+
+    let err: &'static VTemplate<'static> = Box::leak(Box::new(wrap(props_b, render_err)));
+    dom.diff(&mut mutations, ready, err);
+
+    // The `Replace` must target the id that was actually mounted on screen for the component's
+    // root, not the outer template's own id or a default id from a freshly re-rendered subtree.
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::Replace { id } if *id == mounted_root_id)));
+}
+
+#[test]
+fn empty_to_many_attaches_every_child() {
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    static ITEM_TEMPLATE: Template = Template {
+        id: "empty-to-many-item",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn item(key: &'static str, value: &'static str) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: Some(key),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Text {
+                value,
+                id: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let empty: &'static [VTemplate<'static>] = &[];
+    let three_items: &'static [VTemplate<'static>] =
+        Box::leak(Box::new([item("a", "a"), item("b", "b"), item("c", "c")]));
+
+    let ph1 = Cell::new(dom.arena.next());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, empty, three_items, &ph1, &ph2);
+
+    // Every created child must be attached somehow: either replacing the placeholder,
+    // chained via `InsertBefore`, or (the one with nothing after it) `Append`ed.
+    for child in three_items {
+        let id = child.node_id.get();
+        let attached = mutations.iter().any(|m| {
+            matches!(m, Mutation::Append { id: target } if *target == id)
+                || matches!(m, Mutation::InsertBefore { id: target, .. } if *target == id)
+                || matches!(m, Mutation::Replace { .. })
+        });
+        assert!(attached, "child {:?} was created but never attached", id);
+    }
+    assert!(mutations.iter().any(|m| matches!(m, Mutation::Append { .. })));
+}
+
+#[test]
+fn appended_trailing_child_gets_attached() {
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    static ITEM_TEMPLATE: Template = Template {
+        id: "appended-trailing-item",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    fn item(key: &'static str, value: &'static str) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: Some(key),
+            template: ITEM_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Text {
+                value,
+                id: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let one_item: &'static [VTemplate<'static>] = Box::leak(Box::new([item("a", "a")]));
+    let three_items: &'static [VTemplate<'static>] =
+        Box::leak(Box::new([item("a", "a"), item("b", "b"), item("c", "c")]));
+
+    let ph1 = Cell::new(ElementId::default());
+    let ph2 = Cell::new(ElementId::default());
+    dom.diff_fragment(&mut mutations, one_item, three_items, &ph1, &ph2);
+
+    // `c` is the new trailing child with nothing after it to anchor against, so it must be
+    // `Append`ed rather than silently left with no attaching mutation.
+    let c_id = three_items[2].node_id.get();
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::Append { id } if *id == c_id)));
+}
+
+#[test]
+fn component_type_switch_replaces_the_old_id_not_the_new_one() {
+    use std::any::Any;
+
+    static OUTER_TEMPLATE: Template = Template {
+        id: "type-switch-outer",
+        root: TemplateNode::DynamicText(0),
+        node_pathways: &[&[]],
+        attr_pathways: &[],
+    };
+
+    // Zero dynamic nodes each, so a fresh mount of either one needs nothing but its own root id
+    // — the simplest case where a freed-too-early id can be popped right back out for the new
+    // component's own `LoadTemplate`.
+    static TEMPLATE_A: Template = Template {
+        id: "type-switch-a",
+        root: TemplateNode::Text("a"),
+        node_pathways: &[],
+        attr_pathways: &[],
+    };
+
+    static TEMPLATE_B: Template = Template {
+        id: "type-switch-b",
+        root: TemplateNode::Text("b"),
+        node_pathways: &[],
+        attr_pathways: &[],
+    };
+
+    fn render_a(_props: &dyn Any) -> &'static RenderReturn<'static> {
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: TEMPLATE_A,
+            dynamic_nodes: &[],
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn render_b(_props: &dyn Any) -> &'static RenderReturn<'static> {
+        Box::leak(Box::new(RenderReturn::Ready(VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: TEMPLATE_B,
+            dynamic_nodes: &[],
+            dynamic_attrs: &[],
+        })))
+    }
+
+    fn memo(_a: &dyn Any, _b: &dyn Any) -> bool {
+        false
+    }
+
+    fn wrap(name: &'static str, render: fn(&'static dyn Any) -> &'static RenderReturn<'static>) -> VTemplate<'static> {
+        VTemplate {
+            node_id: Default::default(),
+            key: None,
+            template: OUTER_TEMPLATE,
+            dynamic_nodes: Box::leak(Box::new([DynamicNode::Component {
+                name,
+                scope: Default::default(),
+                props: &(),
+                render,
+                memo,
+                mounted: Default::default(),
+            }])),
+            dynamic_attrs: &[],
+        }
+    }
+
+    let mut dom = VirtualDom::default();
+    let mut mutations = Vec::default();
+
+    let left: &'static VTemplate<'static> = Box::leak(Box::new(wrap("A", render_a)));
+    dom.create(&mut mutations, left);
+    let old_id = mutations
+        .iter()
+        .find_map(|m| match m {
+            Mutation::LoadTemplate { name, id } if *name == TEMPLATE_A.id => Some(*id),
+            _ => None,
+        })
+        .expect("create mounts component A's root template");
+    mutations.clear();
+
+    let right: &'static VTemplate<'static> = Box::leak(Box::new(wrap("B", render_b)));
+    dom.diff(&mut mutations, left, right);
+
+    let new_id = mutations
+        .iter()
+        .find_map(|m| match m {
+            Mutation::LoadTemplate { name, id } if *name == TEMPLATE_B.id => Some(*id),
+            _ => None,
+        })
+        .expect("diff mounts component B's root template");
+
+    // The new component must not be mounted under the same id the `Replace` is about to target
+    // — that would mean the old id was freed (and reused) before the mutation referencing it
+    // was pushed, so the stream ends up replacing the node that was just created instead of the
+    // stale one.
+    assert_ne!(old_id, new_id);
+    assert!(mutations
+        .iter()
+        .any(|m| matches!(m, Mutation::Replace { id } if *id == old_id)));
+}
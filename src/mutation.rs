@@ -38,4 +38,28 @@ pub enum Mutation<'a> {
     Replace {
         id: ElementId,
     },
+
+    // Move the element with the given id so it sits directly before `anchor_id`.
+    InsertBefore {
+        id: ElementId,
+        anchor_id: ElementId,
+    },
+
+    // Attach the element with the given id as the last child of its parent, for a newly created
+    // or moved node with no following sibling to anchor an `InsertBefore` against.
+    Append {
+        id: ElementId,
+    },
+
+    // Remove the element with the given id from the renderer entirely.
+    Remove {
+        id: ElementId,
+    },
+
+    // Mount a placeholder standing in for a component that errored or is still pending, so an
+    // error/suspense boundary can swap it out once it resolves, or for a fragment with no
+    // children, so a later diff has something to replace.
+    CreatePlaceholder {
+        id: ElementId,
+    },
 }
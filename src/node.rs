@@ -0,0 +1,105 @@
+use std::any::Any;
+use std::cell::Cell;
+
+use crate::arena::{ElementId, ScopeId};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Template {
+    pub id: &'static str,
+    pub root: TemplateNode,
+    pub node_pathways: &'static [&'static [u8]],
+    pub attr_pathways: &'static [&'static [u8]],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TemplateNode {
+    Element {
+        tag: &'static str,
+        namespace: Option<&'static str>,
+        attrs: &'static [TemplateAttribute],
+        children: &'static [TemplateNode],
+    },
+    Text(&'static str),
+    DynamicText(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateAttribute {
+    pub name: &'static str,
+    pub value: &'static str,
+    pub namespace: Option<&'static str>,
+    pub volatile: bool,
+}
+
+/// What rendering a component produced: a template ready to mount, an error to hand to the
+/// nearest error boundary, or an indication that its data isn't ready yet (for the nearest
+/// suspense boundary). `Err`/`Pending` both mount as a placeholder until a later render
+/// resolves them to `Ready`.
+pub enum RenderReturn<'a> {
+    Ready(VTemplate<'a>),
+    Err(anyhow::Error),
+    Pending,
+}
+
+/// A mounted instance of a [`Template`], carrying the runtime values that fill in its dynamic
+/// slots plus the ids that were assigned to it on creation.
+pub struct VTemplate<'a> {
+    pub node_id: Cell<ElementId>,
+
+    /// Keyed fragment children use this to find themselves again across renders instead of
+    /// relying on their position in the list.
+    pub key: Option<&'a str>,
+
+    pub template: Template,
+    pub dynamic_nodes: &'a [DynamicNode<'a>],
+    pub dynamic_attrs: &'a [AttributeLocation<'a>],
+}
+
+pub enum DynamicNode<'a> {
+    Component {
+        name: &'static str,
+
+        /// Stable across renders of the same mounted component so its identity survives a
+        /// props update.
+        scope: Cell<ScopeId>,
+
+        props: &'a dyn Any,
+
+        /// Renders `props` into the template it mounts. Expected to be pure, but is only ever
+        /// invoked to produce a *new* render — the previously mounted subtree is recovered from
+        /// `mounted` below, never by calling this again on stale props.
+        render: fn(&'a dyn Any) -> &'a RenderReturn<'a>,
+
+        /// Cheap equality check used to skip re-rendering when props haven't meaningfully
+        /// changed.
+        memo: fn(&dyn Any, &dyn Any) -> bool,
+
+        /// The `RenderReturn` actually mounted for this scope, cached at create/diff time. Its
+        /// ids are the real ones assigned on screen, unlike a fresh call to `render`, which would
+        /// hand back an unmounted subtree with every `Cell` at its default value.
+        mounted: Cell<Option<&'a RenderReturn<'a>>>,
+    },
+    Text {
+        value: &'a str,
+        id: Cell<ElementId>,
+    },
+    Fragment {
+        children: &'a [VTemplate<'a>],
+
+        /// Holds the id of the placeholder mounted in this slot while `children` is empty, so
+        /// the location stays addressable (e.g. for a future diff to replace) even though
+        /// nothing real is mounted there.
+        placeholder: Cell<ElementId>,
+    },
+}
+
+pub struct AttributeLocation<'a> {
+    pub mounted_element: Cell<ElementId>,
+    pub attrs: &'a [Attribute<'a>],
+}
+
+pub struct Attribute<'a> {
+    pub name: &'static str,
+    pub value: &'a str,
+    pub namespace: Option<&'static str>,
+}